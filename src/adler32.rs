@@ -0,0 +1,12 @@
+//! Adler-32, the checksum zlib streams are terminated with.
+
+/// Compute the Adler-32 checksum of `bytes`.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,13 @@
+//! CRC-32 (the zlib/PNG variant: polynomial 0xedb88320, init/final XOR 0xffffffff).
+
+/// Compute the CRC-32 of `bytes`.
+pub fn checksum(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { 0xedb88320 ^ (crc >> 1) } else { crc >> 1 };
+        }
+    }
+    crc ^ 0xffffffff
+}
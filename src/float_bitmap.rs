@@ -0,0 +1,100 @@
+//! A floating-point HDR pixel buffer for callers (ray tracers, Monte-Carlo renderers) that
+//! need to accumulate linear light values before quantizing down to an 8-bit `Bitmap`.
+
+use super::{Bitmap, OutOfBounds};
+
+/// A floating-point RGB pixel buffer.
+///
+/// Unlike `Bitmap`, channels aren't clamped to `0..=255` on every write, so samples can be
+/// accumulated into a pixel without forcing premature 8-bit rounding.
+pub struct FloatBitmap {
+    width: i32,
+    height: i32,
+    pixels: Vec<(f32, f32, f32)>,
+}
+
+impl FloatBitmap {
+    /// Create a new blank (black) floating-point bitmap of a specified size.
+    pub fn new(width: i32, height: i32) -> FloatBitmap {
+        FloatBitmap {
+            width: width,
+            height: height,
+            pixels: vec![(0.0, 0.0, 0.0); (width * height) as usize],
+        }
+    }
+
+    /// Get the color currently accumulated at (x, y).
+    ///
+    /// # Panics
+    ///
+    /// Panics if (x, y) is outside the bitmap's bounds.
+    pub fn get_pixel(&self, x: i32, y: i32) -> (f32, f32, f32) {
+        self.pixels[self.index(x, y)]
+    }
+
+    /// Set the color at (x, y), replacing whatever was accumulated there.
+    ///
+    /// # Panics
+    ///
+    /// Panics if (x, y) is outside the bitmap's bounds; use `try_set_pixel` to handle
+    /// out-of-range coordinates without panicking.
+    pub fn set_pixel(&mut self, x: i32, y: i32, color: (f32, f32, f32)) {
+        self.try_set_pixel(x, y, color).expect("set_pixel: (x, y) out of bounds")
+    }
+
+    /// Set the color at (x, y), replacing whatever was accumulated there, or return
+    /// `OutOfBounds` if (x, y) falls outside `0..width` / `0..height` instead of panicking.
+    pub fn try_set_pixel(&mut self, x: i32, y: i32, color: (f32, f32, f32)) -> Result<(), OutOfBounds> {
+        if !self.in_bounds(x, y) {
+            return Err(OutOfBounds);
+        }
+        let i = self.index(x, y);
+        self.pixels[i] = color;
+        Ok(())
+    }
+
+    /// Add `color` to whatever is already accumulated at (x, y), e.g. a new light sample.
+    ///
+    /// # Panics
+    ///
+    /// Panics if (x, y) is outside the bitmap's bounds.
+    pub fn mod_pixel(&mut self, x: i32, y: i32, color: (f32, f32, f32)) {
+        let i = self.index(x, y);
+        let (r, g, b) = self.pixels[i];
+        let (dr, dg, db) = color;
+        self.pixels[i] = (r + dr, g + dg, b + db);
+    }
+
+    /// Whether (x, y) lies within `0..width` / `0..height`.
+    fn in_bounds(&self, x: i32, y: i32) -> bool {
+        x >= 0 && x < self.width && y >= 0 && y < self.height
+    }
+
+    fn index(&self, x: i32, y: i32) -> usize {
+        assert!(self.in_bounds(x, y), "FloatBitmap: (x, y) out of bounds");
+        (y * self.width + x) as usize
+    }
+
+    /// Tone-map this buffer down to a regular 24-bit `Bitmap`.
+    ///
+    /// Each channel is optionally divided by `samples` (the number of contributions
+    /// accumulated per pixel), scaled so that 1.0 maps to 255, and then clamped to `0..=255`.
+    pub fn to_bitmap(&self, samples: Option<f32>) -> Bitmap {
+        let scale = 255.0 / samples.unwrap_or(1.0);
+
+        let mut bitmap = Bitmap::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let (r, g, b) = self.get_pixel(x, y);
+                bitmap.set_pixel(x, y, (quantize(r, scale), quantize(g, scale), quantize(b, scale)));
+            }
+        }
+        bitmap
+    }
+}
+
+/// Scale a linear channel value by `scale` and clamp it into the `0..=255` range a `Bitmap`
+/// can store.
+fn quantize(channel: f32, scale: f32) -> u8 {
+    (channel * scale).max(0.0).min(255.0) as u8
+}